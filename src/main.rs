@@ -1,28 +1,27 @@
+mod node_identity;
+mod sled_store;
+
 use async_std::{io, task};
 use futures::{prelude::*, select};
-use libp2p::kad::record::store::MemoryStore;
-use libp2p::kad::{record::Key, AddProviderOk, Kademlia, KademliaEvent, PeerRecord, PutRecordOk, QueryResult, Quorum, Record};
-use libp2p::{development_transport, identity, mdns::{Mdns, MdnsConfig, MdnsEvent},swarm::{NetworkBehaviourEventProcess, SwarmEvent},NetworkBehaviour,PeerId,Swarm};
+use libp2p::kad::{
+    record::Key, AddProviderOk, GetProvidersOk, GetRecordOk, Kademlia, KademliaEvent, PeerRecord,
+    PutRecordOk, QueryResult, Quorum, Record,
+};
+use libp2p::multiaddr::Protocol;
+use libp2p::{development_transport, mdns::{Mdns, MdnsConfig, MdnsEvent},swarm::{NetworkBehaviourEventProcess, SwarmEvent},Multiaddr,NetworkBehaviour,PeerId,Swarm};
+use sled_store::SledStore;
 use std::error::Error;
+use std::num::NonZeroUsize;
+use std::time::{Duration, Instant};
 
-#[async_std::main]
-async fn main() -> Result<(), Box<dyn Error>>{
-   env_logger::init();
-   let local_key = identity::Keypair::generate_ed25519();
-   let local_peer_id = PeerId::from(local_key.public());
-
-    println!("Local peer id: {:?}", local_peer_id);
-
-   let transport = development_transport(local_key).await?;
-
-   #[derive(NetworkBehaviour)]
-   #[behaviour(event_process = true)]
-   struct MyBehaviour{
-    kademlia: Kademlia<MemoryStore>,
+#[derive(NetworkBehaviour)]
+#[behaviour(event_process = true)]
+struct MyBehaviour{
+    kademlia: Kademlia<SledStore>,
     mdns: Mdns
-   }
+}
 
-   impl NetworkBehaviourEventProcess<MdnsEvent> for MyBehaviour{
+impl NetworkBehaviourEventProcess<MdnsEvent> for MyBehaviour{
     fn inject_event(&mut self, event: MdnsEvent) {
         if let MdnsEvent::Discovered(list) = event{
             for (peer_id, multiaddr) in list{
@@ -30,58 +29,91 @@ async fn main() -> Result<(), Box<dyn Error>>{
             }
         }
     }
-   }
+}
 
-   impl NetworkBehaviourEventProcess<KademliaEvent> for MyBehaviour {
+impl NetworkBehaviourEventProcess<KademliaEvent> for MyBehaviour {
     fn inject_event(&mut self, event: KademliaEvent) {
         match event{
-            KademliaEvent::OutboundQueryCompleted { result, ..} => match result{
-                QueryResult::GetProviders(Ok(ok)) => {
-                    for peer in ok.providers{
-                        println!("Peer {:?} provides key {:?}", peer, std::str::from_utf8(ok.key.as_ref()).unwrap());
+            KademliaEvent::OutboundQueryProgressed { id, result, step, ..} => {
+                match result{
+                    QueryResult::GetProviders(Ok(GetProvidersOk::FoundProviders { key, providers })) => {
+                        for peer in providers{
+                            println!("Peer {:?} provides key {:?}", peer, std::str::from_utf8(key.as_ref()).unwrap());
+                        }
                     }
-                }
-                QueryResult::GetProviders(Err(err)) => {
-                    eprintln!("Failed to get providers: {:?}", err);
-                }
-                QueryResult::GetRecord(Ok(ok)) => {
-                    for PeerRecord {
+                    QueryResult::GetProviders(Ok(GetProvidersOk::FinishedWithNoAdditionalRecord { .. })) => {}
+                    QueryResult::GetProviders(Err(err)) => {
+                        eprintln!("Failed to get providers: {:?}", err);
+                    }
+                    QueryResult::GetRecord(Ok(GetRecordOk::FoundRecord(PeerRecord {
                         record: Record { key, value, ..}, ..
-                    } in ok.records{
+                    }))) => {
                         println!("Got record {:?} {:?}", std::str::from_utf8(key.as_ref()).unwrap(), std::str::from_utf8(&value).unwrap());
                     }
-                }
-                QueryResult::GetRecord(Err(err)) => {
-                    eprintln!("Failed to get record: {:?}", err);
-                }
-                QueryResult::PutRecord(Ok(PutRecordOk {key})) => {
-                    println!("Successfully put record {:?}", std::str::from_utf8(key.as_ref()).unwrap());
-                }
-                QueryResult::PutRecord(Err(err)) => {
-                    eprintln!("Failed to put record {:?}", err);
-                }
-                QueryResult::StartProviding(Ok(AddProviderOk{key})) => {
-                    println!("Successfully put provider record {:?}", std::str::from_utf8(key.as_ref()).unwrap());
-                }
-                QueryResult::StartProviding(Err(err)) => {
-                    eprintln!("Failed to put provider record: {:?}", err);
-                }
-                QueryResult::GetClosestPeers(Ok(ok)) => {
-                    for peer in ok.peers{
-                        println!("Closest peer: {:?}", peer);
+                    QueryResult::GetRecord(Ok(GetRecordOk::FinishedWithNoAdditionalRecord { .. })) => {}
+                    QueryResult::GetRecord(Err(err)) => {
+                        eprintln!("Failed to get record: {:?}", err);
+                    }
+                    QueryResult::PutRecord(Ok(PutRecordOk {key})) => {
+                        println!("Successfully put record {:?}", std::str::from_utf8(key.as_ref()).unwrap());
                     }
+                    QueryResult::PutRecord(Err(err)) => {
+                        eprintln!("Failed to put record {:?}", err);
+                    }
+                    QueryResult::StartProviding(Ok(AddProviderOk{key})) => {
+                        println!("Successfully put provider record {:?}", std::str::from_utf8(key.as_ref()).unwrap());
+                    }
+                    QueryResult::StartProviding(Err(err)) => {
+                        eprintln!("Failed to put provider record: {:?}", err);
+                    }
+                    QueryResult::GetClosestPeers(Ok(ok)) => {
+                        for peer in ok.peers{
+                            println!("Closest peer: {:?}", peer);
+                        }
+                    }
+                    QueryResult::GetClosestPeers(Err(err)) => {
+                        eprintln!("Failed to get closest peers: {:?}", err);
+                    }
+                    QueryResult::Bootstrap(Ok(ok)) => {
+                        println!("Bootstrap progressed, {:?} peers remaining", ok.num_remaining);
+                    }
+                    QueryResult::Bootstrap(Err(err)) => {
+                        eprintln!("Failed to bootstrap: {:?}", err);
+                    }
+                    _ => {}
                 }
-                QueryResult::GetClosestPeers(Err(err)) => {
-                    eprintln!("Failed to get closest peers: {:?}", err);
+                if step.last{
+                    println!("query {:?} finished", id);
                 }
-                _ => {}
-            },
+            }
             _ => {}
         }
     }
-   }
+}
+
+/// Directory the sled-backed record store is persisted under, relative to
+/// the current working directory the node is started from.
+const STORE_PATH: &str = "kadvs-store";
+
+/// Directory the node's persistent identity key and mnemonic are stored
+/// under, relative to the current working directory the node is started
+/// from.
+const IDENTITY_PATH: &str = "kadvs-identity";
+
+#[async_std::main]
+async fn main() -> Result<(), Box<dyn Error>>{
+   env_logger::init();
+   let local_key = node_identity::load_or_generate(IDENTITY_PATH)
+       .expect("Failed to load or generate node identity");
+   let local_peer_id = PeerId::from(local_key.public());
+
+    println!("Local peer id: {:?}", local_peer_id);
+
+   let transport = development_transport(local_key).await?;
+
    let mut swarm = {
-    let store = MemoryStore::new(local_peer_id);
+    let store = SledStore::new(local_peer_id, STORE_PATH)
+        .expect("Failed to open sled record store");
     let kademlia = Kademlia::new(local_peer_id, store);
     let mdns = task::block_on(Mdns::new(MdnsConfig::default()))?;
     let behaviour = MyBehaviour{kademlia, mdns};
@@ -91,18 +123,36 @@ async fn main() -> Result<(), Box<dyn Error>>{
    swarm.listen_on("/ip4/0.0.0.0/tcp/0".parse()?)?;
    loop{
     select!{
-        line = stdin.select_next_some() => handle_input_line(&mut swarm.behaviour_mut().kademlia, line.expect("Stdin not to close")),
+        line = stdin.select_next_some() => handle_input_line(&mut swarm, line.expect("Stdin not to close")),
         event = swarm.select_next_some() => match event{
             SwarmEvent::NewListenAddr {address, ..} =>{
                 println!("Listening in {:?}", address);
             }
+            SwarmEvent::ConnectionEstablished {peer_id, endpoint, ..} => {
+                swarm.behaviour_mut().kademlia.add_address(&peer_id, endpoint.get_remote_address().clone());
+            }
             _ => {}
         }
     }
    }
 }
 
-fn handle_input_line(kademlia: &mut Kademlia<MemoryStore>, line: String){
+/// Parses the optional trailing quorum argument accepted by `GET` and `PUT`:
+/// `ONE`, `MAJORITY`, `ALL`, or `N:<k>` for an explicit `Quorum::N`.
+fn parse_quorum(arg: &str) -> Option<Quorum> {
+    match arg {
+        "ONE" => Some(Quorum::One),
+        "MAJORITY" => Some(Quorum::Majority),
+        "ALL" => Some(Quorum::All),
+        n => n
+            .strip_prefix("N:")
+            .and_then(|n| n.parse::<usize>().ok())
+            .and_then(NonZeroUsize::new)
+            .map(Quorum::N),
+    }
+}
+
+fn handle_input_line(swarm: &mut Swarm<MyBehaviour>, line: String){
     let mut args = line.split(' ');
     match args.next(){
         Some("GET") => {
@@ -115,7 +165,16 @@ fn handle_input_line(kademlia: &mut Kademlia<MemoryStore>, line: String){
                     }
                 }
             };
-            kademlia.get_record(key, Quorum::One);
+            let quorum = match args.next().map(parse_quorum) {
+                Some(Some(quorum)) => quorum,
+                Some(None) => {
+                    eprintln!("Invalid quorum, expected ONE, MAJORITY, ALL or N:<k>");
+                    return;
+                }
+                None => Quorum::One,
+            };
+            println!("Getting with quorum {:?}", quorum);
+            swarm.behaviour_mut().kademlia.get_record(key, quorum);
         }
         Some("GET_PROVIDERS") => {
             let key = {
@@ -127,7 +186,7 @@ fn handle_input_line(kademlia: &mut Kademlia<MemoryStore>, line: String){
                     }
                 }
             };
-            kademlia.get_providers(key);
+            swarm.behaviour_mut().kademlia.get_providers(key);
         }
         Some("PUT") => {
             let key = {
@@ -148,8 +207,25 @@ fn handle_input_line(kademlia: &mut Kademlia<MemoryStore>, line: String){
                     }
                 }
             };
-            let record = Record{key, value, publisher: None, expires: None};
-            kademlia.put_record(record, Quorum::One).expect("Failed to store record locally");
+            let quorum = match args.next().map(parse_quorum) {
+                Some(Some(quorum)) => quorum,
+                Some(None) => {
+                    eprintln!("Invalid quorum, expected ONE, MAJORITY, ALL or N:<k>");
+                    return;
+                }
+                None => Quorum::One,
+            };
+            let expires = match args.next().map(|ttl| ttl.parse::<u64>()) {
+                Some(Ok(ttl_secs)) => Some(Instant::now() + Duration::from_secs(ttl_secs)),
+                Some(Err(_)) => {
+                    eprintln!("Invalid ttl_secs");
+                    return;
+                }
+                None => None,
+            };
+            println!("Putting with quorum {:?}, expires {:?}", quorum, expires);
+            let record = Record{key, value, publisher: None, expires};
+            swarm.behaviour_mut().kademlia.put_record(record, quorum).expect("Failed to store record locally");
         }
         Some("PUT_PROVIDER") => {
             let key = {
@@ -161,7 +237,9 @@ fn handle_input_line(kademlia: &mut Kademlia<MemoryStore>, line: String){
                     }
                 }
             };
-            kademlia
+            swarm
+                .behaviour_mut()
+                .kademlia
                 .start_providing(key)
                 .expect("Failed to start providing key");
         }
@@ -175,10 +253,87 @@ fn handle_input_line(kademlia: &mut Kademlia<MemoryStore>, line: String){
                     }
                 }
             };
-            kademlia.get_closest_peers(key);
+            swarm.behaviour_mut().kademlia.get_closest_peers(key);
+        }
+        Some("DIAL") => {
+            let addr: Multiaddr = match args.next().map(|addr| addr.parse()) {
+                Some(Ok(addr)) => addr,
+                Some(Err(_)) => {
+                    eprintln!("Invalid multiaddr");
+                    return;
+                }
+                None => {
+                    eprintln!("Expected multiaddr");
+                    return;
+                }
+            };
+            if let Some(Protocol::P2p(hash)) = addr.iter().last() {
+                if let Ok(peer_id) = PeerId::from_multihash(hash) {
+                    swarm.behaviour_mut().kademlia.add_address(&peer_id, addr.clone());
+                }
+            }
+            if let Err(err) = swarm.dial(addr) {
+                eprintln!("Failed to dial: {:?}", err);
+            }
+        }
+        Some("BOOTSTRAP") => {
+            if let Err(err) = swarm.behaviour_mut().kademlia.bootstrap() {
+                eprintln!("Failed to start bootstrap: {:?}", err);
+            }
+        }
+        Some("STOP_PROVIDING") => {
+            let key = {
+                match args.next(){
+                    Some(key) => Key::new(&key),
+                    None => {
+                        eprintln!("Expected key");
+                        return;
+                    }
+                }
+            };
+            swarm.behaviour_mut().kademlia.stop_providing(&key);
+        }
+        Some("REMOVE") => {
+            let key = {
+                match args.next(){
+                    Some(key) => Key::new(&key),
+                    None => {
+                        eprintln!("Expected key");
+                        return;
+                    }
+                }
+            };
+            swarm.behaviour_mut().kademlia.remove_record(&key);
         }
         _ => {
-            eprintln!("expected GET, GET_PROVIDERS, PUT, PUT_PROVIDER or CLOSEST_PEERS");
+            eprintln!("expected GET, GET_PROVIDERS, PUT, PUT_PROVIDER, CLOSEST_PEERS, DIAL, BOOTSTRAP, STOP_PROVIDING or REMOVE");
         }
     }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_quorum_keywords() {
+        assert_eq!(parse_quorum("ONE"), Some(Quorum::One));
+        assert_eq!(parse_quorum("MAJORITY"), Some(Quorum::Majority));
+        assert_eq!(parse_quorum("ALL"), Some(Quorum::All));
+    }
+
+    #[test]
+    fn parse_quorum_explicit_n() {
+        assert_eq!(
+            parse_quorum("N:3"),
+            Some(Quorum::N(NonZeroUsize::new(3).unwrap()))
+        );
+    }
+
+    #[test]
+    fn parse_quorum_rejects_garbage() {
+        assert_eq!(parse_quorum("N:0"), None);
+        assert_eq!(parse_quorum("N:nope"), None);
+        assert_eq!(parse_quorum("garbage"), None);
+    }
 }
\ No newline at end of file
@@ -0,0 +1,80 @@
+//! Persistent node identity, so a node keeps the same `PeerId` (and thus
+//! the same place in peers' routing tables) across restarts instead of
+//! generating a fresh keypair every run.
+
+use bip39::Mnemonic;
+use libp2p::identity::Keypair;
+use std::io;
+use std::path::Path;
+
+const KEY_FILE: &str = "identity.key";
+const MNEMONIC_FILE: &str = "identity.mnemonic";
+
+/// Loads the keypair stored under `dir`, or generates and persists a new
+/// one (along with its BIP39 mnemonic, so the identity can be backed up and
+/// restored as a word list) if none exists yet.
+pub fn load_or_generate(dir: impl AsRef<Path>) -> io::Result<Keypair> {
+    let dir = dir.as_ref();
+    std::fs::create_dir_all(dir)?;
+    let key_path = dir.join(KEY_FILE);
+    let mnemonic_path = dir.join(MNEMONIC_FILE);
+
+    if let Ok(bytes) = std::fs::read(&key_path) {
+        if let Ok(keypair) = Keypair::from_protobuf_encoding(&bytes) {
+            return Ok(keypair);
+        }
+    }
+
+    let stored_keypair = std::fs::read_to_string(&mnemonic_path)
+        .ok()
+        .and_then(|phrase| keypair_from_mnemonic(phrase.trim()).ok());
+
+    let keypair = match stored_keypair {
+        Some(keypair) => keypair,
+        None => {
+            let mnemonic = Mnemonic::generate(12).expect("12 is a valid BIP39 word count");
+            write_secret_file(&mnemonic_path, mnemonic.to_string().as_bytes())?;
+            keypair_from_mnemonic(&mnemonic.to_string())
+                .expect("freshly generated mnemonic is valid")
+        }
+    };
+
+    write_secret_file(
+        &key_path,
+        &keypair
+            .to_protobuf_encoding()
+            .expect("ed25519 keys always encode to protobuf"),
+    )?;
+    Ok(keypair)
+}
+
+/// Writes secret key material to `path`, creating it already restricted to
+/// the owner (`0600`) on unix so there's no window where the file is
+/// briefly readable at the default mode before being chmod'd.
+fn write_secret_file(path: &Path, contents: &[u8]) -> io::Result<()> {
+    #[cfg(unix)]
+    {
+        use std::io::Write;
+        use std::os::unix::fs::OpenOptionsExt;
+        let mut file = std::fs::OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .mode(0o600)
+            .open(path)?;
+        file.write_all(contents)
+    }
+    #[cfg(not(unix))]
+    {
+        std::fs::write(path, contents)
+    }
+}
+
+fn keypair_from_mnemonic(phrase: &str) -> Result<Keypair, bip39::Error> {
+    let mnemonic = Mnemonic::parse(phrase)?;
+    let seed = mnemonic.to_seed("");
+    Ok(
+        Keypair::ed25519_from_bytes(seed[..32].try_into().expect("seed is at least 32 bytes"))
+            .expect("32 bytes are a valid ed25519 secret key"),
+    )
+}
@@ -0,0 +1,312 @@
+//! A `RecordStore` implementation backed by a `sled` database so that
+//! records and provider records survive process restarts.
+
+use libp2p::kad::record::store::{Error, RecordStore, Result};
+use libp2p::kad::record::{Key, ProviderRecord, Record};
+use libp2p::kad::K_VALUE;
+use libp2p::PeerId;
+use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
+use std::collections::HashSet;
+use std::path::Path;
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+/// Converts an `Instant` deadline to an absolute Unix timestamp. `Instant`
+/// has no stable epoch to serialize against, so we re-anchor it to
+/// `SystemTime`'s epoch, which survives a restart.
+fn instant_to_unix_secs(instant: Instant) -> u64 {
+    let now_instant = Instant::now();
+    let deadline = if instant >= now_instant {
+        SystemTime::now() + instant.saturating_duration_since(now_instant)
+    } else {
+        SystemTime::now()
+            .checked_sub(now_instant.saturating_duration_since(instant))
+            .unwrap_or(UNIX_EPOCH)
+    };
+    deadline.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs()
+}
+
+/// Converts an absolute Unix timestamp back to an `Instant`, anchored to the
+/// current moment. A deadline already in the past collapses to "now".
+fn unix_secs_to_instant(secs: u64) -> Instant {
+    let deadline = UNIX_EPOCH + std::time::Duration::from_secs(secs);
+    match deadline.duration_since(SystemTime::now()) {
+        Ok(remaining) => Instant::now() + remaining,
+        Err(_) => Instant::now(),
+    }
+}
+
+/// On-disk representation of a [`Record`].
+#[derive(Serialize, Deserialize)]
+struct StoredRecord {
+    value: Vec<u8>,
+    publisher: Option<Vec<u8>>,
+    expires_at_unix_secs: Option<u64>,
+}
+
+impl StoredRecord {
+    fn from_record(record: &Record) -> Self {
+        StoredRecord {
+            value: record.value.clone(),
+            publisher: record.publisher.map(|p| p.to_bytes()),
+            expires_at_unix_secs: record.expires.map(instant_to_unix_secs),
+        }
+    }
+
+    fn into_record(self, key: Key) -> Record {
+        Record {
+            key,
+            value: self.value,
+            publisher: self
+                .publisher
+                .and_then(|bytes| PeerId::from_bytes(&bytes).ok()),
+            expires: self.expires_at_unix_secs.map(unix_secs_to_instant),
+        }
+    }
+}
+
+/// On-disk representation of a [`ProviderRecord`].
+#[derive(Serialize, Deserialize)]
+struct StoredProviderRecord {
+    provider: Vec<u8>,
+    expires_at_unix_secs: Option<u64>,
+    addresses: Vec<String>,
+}
+
+impl StoredProviderRecord {
+    fn from_record(record: &ProviderRecord) -> Self {
+        StoredProviderRecord {
+            provider: record.provider.to_bytes(),
+            expires_at_unix_secs: record.expires.map(instant_to_unix_secs),
+            addresses: record.addresses.iter().map(|a| a.to_string()).collect(),
+        }
+    }
+
+    fn into_record(self, key: Key) -> Option<ProviderRecord> {
+        Some(ProviderRecord {
+            key,
+            provider: PeerId::from_bytes(&self.provider).ok()?,
+            expires: self.expires_at_unix_secs.map(unix_secs_to_instant),
+            addresses: self
+                .addresses
+                .iter()
+                .filter_map(|a| a.parse().ok())
+                .collect(),
+        })
+    }
+}
+
+/// Prefix shared by every provider-tree entry for `key`: a big-endian length
+/// header followed by the key's raw bytes. Without the length header, a key
+/// that is a byte-prefix of another key (e.g. `"ab"` vs. `"abc"`) would match
+/// the other key's entries under `scan_prefix`.
+fn provider_tree_prefix(key: &Key) -> Vec<u8> {
+    let key_bytes = key.as_ref();
+    let mut bytes = Vec::with_capacity(4 + key_bytes.len());
+    bytes.extend_from_slice(&(key_bytes.len() as u32).to_be_bytes());
+    bytes.extend_from_slice(key_bytes);
+    bytes
+}
+
+fn provider_tree_key(key: &Key, provider: &PeerId) -> Vec<u8> {
+    let mut bytes = provider_tree_prefix(key);
+    bytes.extend_from_slice(&provider.to_bytes());
+    bytes
+}
+
+/// Resource bounds for a [`SledStore`], mirroring `MemoryStoreConfig` so a
+/// disk-backed store doesn't trade the in-memory store's protection against
+/// unbounded growth from remote `PUT`/`PUT_PROVIDER` traffic for persistence.
+pub struct SledStoreConfig {
+    pub max_records: usize,
+    pub max_value_bytes: usize,
+    pub max_providers_per_key: usize,
+    pub max_provided_keys: usize,
+}
+
+impl Default for SledStoreConfig {
+    fn default() -> Self {
+        SledStoreConfig {
+            max_records: 1024,
+            max_value_bytes: 65 * 1024,
+            max_providers_per_key: K_VALUE.get(),
+            max_provided_keys: 1024,
+        }
+    }
+}
+
+/// A [`RecordStore`] that persists records and provider records to a `sled`
+/// database, so a node's DHT state survives a restart instead of living only
+/// in memory.
+pub struct SledStore {
+    local_id: PeerId,
+    records: sled::Tree,
+    providers: sled::Tree,
+    config: SledStoreConfig,
+}
+
+impl SledStore {
+    /// Opens (or creates) a sled-backed store at `path` with the default
+    /// resource bounds.
+    pub fn new(local_id: PeerId, path: impl AsRef<Path>) -> sled::Result<Self> {
+        Self::with_config(local_id, path, SledStoreConfig::default())
+    }
+
+    /// Opens (or creates) a sled-backed store at `path` with custom resource
+    /// bounds.
+    pub fn with_config(
+        local_id: PeerId,
+        path: impl AsRef<Path>,
+        config: SledStoreConfig,
+    ) -> sled::Result<Self> {
+        let db = sled::open(path)?;
+        let records = db.open_tree("records")?;
+        let providers = db.open_tree("providers")?;
+        Ok(SledStore {
+            local_id,
+            records,
+            providers,
+            config,
+        })
+    }
+
+    /// Number of distinct keys that currently have at least one provider
+    /// recorded, used to bound growth of the providers tree.
+    fn provided_key_count(&self) -> usize {
+        self.providers
+            .iter()
+            .flatten()
+            .filter_map(|(tree_key, _)| {
+                let key_len = u32::from_be_bytes(tree_key.get(0..4)?.try_into().ok()?) as usize;
+                tree_key.get(4..4 + key_len).map(|bytes| bytes.to_vec())
+            })
+            .collect::<HashSet<_>>()
+            .len()
+    }
+}
+
+impl RecordStore for SledStore {
+    type RecordsIter<'a> = Box<dyn Iterator<Item = Cow<'a, Record>> + 'a>;
+    type ProvidedIter<'a> = Box<dyn Iterator<Item = Cow<'a, ProviderRecord>> + 'a>;
+
+    fn get(&self, k: &Key) -> Option<Cow<'_, Record>> {
+        let bytes = self.records.get(k.as_ref()).ok().flatten()?;
+        let stored: StoredRecord = bincode::deserialize(&bytes).ok()?;
+        Some(Cow::Owned(stored.into_record(k.clone())))
+    }
+
+    fn put(&mut self, record: Record) -> Result<()> {
+        if record.value.len() >= self.config.max_value_bytes {
+            return Err(Error::ValueTooLarge);
+        }
+        let is_new = !self
+            .records
+            .contains_key(record.key.as_ref())
+            .unwrap_or(false);
+        if is_new && self.records.len() >= self.config.max_records {
+            return Err(Error::MaxRecords);
+        }
+        let stored = StoredRecord::from_record(&record);
+        let bytes = bincode::serialize(&stored).map_err(|_| Error::ValueTooLarge)?;
+        self.records
+            .insert(record.key.as_ref(), bytes)
+            .map_err(|_| Error::ValueTooLarge)?;
+        Ok(())
+    }
+
+    fn remove(&mut self, k: &Key) {
+        let _ = self.records.remove(k.as_ref());
+    }
+
+    fn records(&self) -> Self::RecordsIter<'_> {
+        Box::new(self.records.iter().filter_map(|entry| {
+            let (key, bytes) = entry.ok()?;
+            let stored: StoredRecord = bincode::deserialize(&bytes).ok()?;
+            Some(Cow::Owned(stored.into_record(Key::from(key.to_vec()))))
+        }))
+    }
+
+    fn add_provider(&mut self, record: ProviderRecord) -> Result<()> {
+        let existing = self.providers(&record.key);
+        let already_provider = existing.iter().any(|p| p.provider == record.provider);
+
+        if !already_provider {
+            if existing.is_empty() && self.provided_key_count() >= self.config.max_provided_keys {
+                return Err(Error::MaxProvidedKeys);
+            }
+            if record.provider != self.local_id
+                && existing.len() >= self.config.max_providers_per_key
+            {
+                // Don't accept more providers for this key.
+                return Ok(());
+            }
+        }
+
+        let tree_key = provider_tree_key(&record.key, &record.provider);
+        let stored = StoredProviderRecord::from_record(&record);
+        let bytes = bincode::serialize(&stored).map_err(|_| Error::ValueTooLarge)?;
+        self.providers
+            .insert(tree_key, bytes)
+            .map_err(|_| Error::ValueTooLarge)?;
+        Ok(())
+    }
+
+    fn providers(&self, key: &Key) -> Vec<ProviderRecord> {
+        self.providers
+            .scan_prefix(provider_tree_prefix(key))
+            .filter_map(|entry| {
+                let (_, bytes) = entry.ok()?;
+                let stored: StoredProviderRecord = bincode::deserialize(&bytes).ok()?;
+                stored.into_record(key.clone())
+            })
+            .collect()
+    }
+
+    fn provided(&self) -> Self::ProvidedIter<'_> {
+        let local_id = self.local_id;
+        Box::new(self.providers.iter().filter_map(move |entry| {
+            let (tree_key, bytes) = entry.ok()?;
+            let stored: StoredProviderRecord = bincode::deserialize(&bytes).ok()?;
+            let key_len = u32::from_be_bytes(tree_key.get(0..4)?.try_into().ok()?) as usize;
+            let key = Key::from(tree_key.get(4..4 + key_len)?.to_vec());
+            let record = stored.into_record(key)?;
+            (record.provider == local_id).then(|| Cow::Owned(record))
+        }))
+    }
+
+    fn remove_provider(&mut self, k: &Key, p: &PeerId) {
+        let _ = self.providers.remove(provider_tree_key(k, p));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn expiry_round_trips_through_unix_secs() {
+        let deadline = Instant::now() + Duration::from_secs(30);
+        let recovered = unix_secs_to_instant(instant_to_unix_secs(deadline));
+        let delta = if recovered >= deadline {
+            recovered.duration_since(deadline)
+        } else {
+            deadline.duration_since(recovered)
+        };
+        assert!(delta < Duration::from_secs(1));
+    }
+
+    #[test]
+    fn expiry_in_the_past_collapses_to_now() {
+        let now = Instant::now();
+        let recovered = unix_secs_to_instant(0);
+        assert!(recovered <= now + Duration::from_millis(10));
+    }
+
+    #[test]
+    fn provider_tree_prefix_does_not_collide_on_key_prefixes() {
+        let short = provider_tree_prefix(&Key::new(&"ab"));
+        let long = provider_tree_prefix(&Key::new(&"abc"));
+        assert!(!long.starts_with(&short));
+    }
+}